@@ -0,0 +1,86 @@
+/// Long running operations which report their progress to an `EventSink`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Flash,
+    ChipErase,
+    RomTableScan,
+    MemoryTransfer,
+}
+
+/// A state change of a long running operation, passed to an `EventSink`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The operation started. `total` is the amount of work in operation specific units
+    /// (bytes, sectors, ROM table entries) if it is known up front.
+    Started {
+        operation: Operation,
+        total: Option<usize>,
+    },
+    /// `done` units of work of the operation are finished.
+    Progress {
+        operation: Operation,
+        done: usize,
+    },
+    Finished {
+        operation: Operation,
+    },
+    /// Something went wrong without aborting the operation.
+    Warning {
+        operation: Operation,
+        message: String,
+    },
+}
+
+/// Receives the events of long running operations, so frontends don't have to poll or parse logs.
+///
+/// Any `FnMut(&Event)` closure is an `EventSink`.
+pub trait EventSink {
+    fn event(&mut self, event: &Event);
+}
+
+impl<F: FnMut(&Event)> EventSink for F {
+    fn event(&mut self, event: &Event) {
+        self(event)
+    }
+}
+
+/// An `EventSink` discarding all events.
+pub struct NullSink;
+
+impl EventSink for NullSink {
+    fn event(&mut self, _event: &Event) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(sink: &mut dyn EventSink) {
+        sink.event(&Event::Started {
+            operation: Operation::ChipErase,
+            total: Some(2),
+        });
+        sink.event(&Event::Progress {
+            operation: Operation::ChipErase,
+            done: 1,
+        });
+        sink.event(&Event::Finished {
+            operation: Operation::ChipErase,
+        });
+    }
+
+    #[test]
+    fn closures_are_sinks() {
+        let mut events = Vec::new();
+        report(&mut |event: &Event| events.push(event.clone()));
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[1],
+            Event::Progress {
+                operation: Operation::ChipErase,
+                done: 1
+            }
+        );
+        report(&mut NullSink);
+    }
+}
@@ -1,4 +1,12 @@
 pub mod protocol;
+pub mod probe;
+pub mod transport;
+pub mod mock;
+pub mod usb_log;
+pub mod event;
+pub mod trace_buffer;
+pub mod svf;
+pub mod log_router;
 mod common;
 
 #[cfg(test)]
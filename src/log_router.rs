@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+/// A target output channel feeding a `LogRouter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogSource {
+    /// An RTT up channel.
+    Rtt(usize),
+    /// An ITM stimulus port.
+    Itm(u8),
+    Semihosting,
+}
+
+impl fmt::Display for LogSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogSource::Rtt(channel) => write!(f, "rtt:{}", channel),
+            LogSource::Itm(port) => write!(f, "itm:{}", port),
+            LogSource::Semihosting => write!(f, "semihosting"),
+        }
+    }
+}
+
+/// One line of target output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    /// When the first byte of the line was received, relative to a point in time chosen by the caller.
+    pub timestamp: Duration,
+    pub source: LogSource,
+    /// The line without its line ending. Invalid UTF-8 is replaced.
+    pub message: String,
+}
+
+/// Receives the records of a `LogRouter`.
+///
+/// Any `FnMut(&LogRecord)` closure is a `LogSink`, and so is the `Sender` of a channel.
+pub trait LogSink {
+    fn record(&mut self, record: &LogRecord);
+}
+
+impl<F: FnMut(&LogRecord)> LogSink for F {
+    fn record(&mut self, record: &LogRecord) {
+        self(record)
+    }
+}
+
+impl LogSink for Sender<LogRecord> {
+    fn record(&mut self, record: &LogRecord) {
+        // A dropped receiver only means nobody is interested anymore.
+        let _ = self.send(record.clone());
+    }
+}
+
+/// A `LogSink` writing one tagged line per record, e.g. to stdout or a file.
+pub struct WriterSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> LogSink for WriterSink<W> {
+    fn record(&mut self, record: &LogRecord) {
+        if let Err(e) = writeln!(
+            self.writer,
+            "[{:>12.6}] {}: {}",
+            record.timestamp.as_secs_f64(),
+            record.source,
+            record.message
+        ) {
+            log::warn!("Could not write log record: {}", e);
+        }
+    }
+}
+
+/// Merges the output of several RTT channels, ITM ports and semihosting into one ordered stream of lines.
+///
+/// Sources are pushed raw bytes as they are polled. Complete lines are buffered until `flush`,
+/// which hands them to all sinks ordered by timestamp, so output of sources polled one after another
+/// still interleaves correctly. Lines split across several pushes are joined first.
+pub struct LogRouter {
+    pending: Vec<LogRecord>,
+    partial: HashMap<LogSource, (Duration, Vec<u8>)>,
+    sinks: Vec<Box<dyn LogSink + Send>>,
+}
+
+impl LogRouter {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            partial: HashMap::new(),
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Adds a sink receiving every record.
+    pub fn sink<S: LogSink + Send + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Adds `bytes` received from `source` at `timestamp`.
+    pub fn push(&mut self, source: LogSource, timestamp: Duration, bytes: &[u8]) {
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let (started, line) = self
+                .partial
+                .entry(source)
+                .or_insert_with(|| (timestamp, Vec::new()));
+            match bytes.iter().position(|&b| b == b'\n') {
+                Some(end) => {
+                    line.extend_from_slice(&bytes[..end]);
+                    let record = record(source, *started, line);
+                    self.partial.remove(&source);
+                    self.pending.push(record);
+                    bytes = &bytes[end + 1..];
+                }
+                None => {
+                    line.extend_from_slice(bytes);
+                    bytes = &[];
+                }
+            }
+        }
+    }
+
+    /// Hands all complete lines to the sinks, oldest first.
+    pub fn flush(&mut self) {
+        // The sort is stable, so lines of one source keep their order even with equal timestamps.
+        self.pending.sort_by_key(|record| record.timestamp);
+        for record in self.pending.drain(..) {
+            for sink in &mut self.sinks {
+                sink.record(&record);
+            }
+        }
+    }
+
+    /// Treats all incomplete lines as complete and flushes them with everything else.
+    pub fn finish(&mut self) {
+        for (source, (started, line)) in self.partial.drain() {
+            self.pending.push(record(source, started, &line));
+        }
+        self.flush();
+    }
+}
+
+impl Default for LogRouter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn record(source: LogSource, timestamp: Duration, line: &[u8]) -> LogRecord {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    LogRecord {
+        timestamp,
+        source,
+        message: String::from_utf8_lossy(line).into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn merges_sources_in_timestamp_order() {
+        let (sender, receiver) = channel();
+        let mut router = LogRouter::new().sink(sender);
+        router.push(LogSource::Rtt(0), ms(10), b"first\r\nthi");
+        router.push(LogSource::Itm(1), ms(20), b"second\n");
+        router.push(LogSource::Rtt(0), ms(30), b"rd\n");
+        router.push(LogSource::Semihosting, ms(40), b"unfinished");
+        router.flush();
+
+        let records: Vec<LogRecord> = receiver.try_iter().collect();
+        let lines: Vec<(LogSource, &str)> = records
+            .iter()
+            .map(|r| (r.source, r.message.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (LogSource::Rtt(0), "first"),
+                (LogSource::Rtt(0), "third"),
+                (LogSource::Itm(1), "second"),
+            ]
+        );
+        assert_eq!(records[1].timestamp, ms(10));
+
+        router.finish();
+        assert_eq!(receiver.try_recv().unwrap().message, "unfinished");
+    }
+
+    #[test]
+    fn writer_sink_tags_lines() {
+        let mut sink = WriterSink::new(Vec::new());
+        sink.record(&LogRecord {
+            timestamp: ms(1500),
+            source: LogSource::Rtt(2),
+            message: "hello".to_string(),
+        });
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            "[    1.500000] rtt:2: hello\n"
+        );
+    }
+}
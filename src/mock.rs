@@ -0,0 +1,371 @@
+use std::collections::VecDeque;
+
+use crate::transport::{ProbeTransport, TransportError, TransportPolicy};
+
+/// A single transfer between host and probe as seen on a `ProbeTransport`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exchange {
+    Command(Vec<u8>),
+    BulkOut(Vec<u8>),
+    BulkIn(Vec<u8>),
+    ControlOut {
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: Vec<u8>,
+    },
+    ControlIn {
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: Vec<u8>,
+    },
+    /// A transfer which failed.
+    /// When replayed, the next transfer fails with this error whatever kind it is.
+    Error(TransportError),
+}
+
+/// A `ProbeTransport` which replays a scripted or recorded list of exchanges.
+///
+/// Every outgoing transfer has to match the next exchange in the script,
+/// every incoming transfer is answered with the data of the next exchange.
+/// This allows testing probe drivers and the layers on top of them without hardware.
+pub struct FakeTransport {
+    script: VecDeque<Exchange>,
+    policy: TransportPolicy,
+}
+
+impl FakeTransport {
+    pub fn new(script: Vec<Exchange>) -> Self {
+        Self {
+            script: script.into(),
+            policy: TransportPolicy::default(),
+        }
+    }
+
+    /// Returns `true` if every scripted exchange has been consumed.
+    pub fn is_done(&self) -> bool {
+        self.script.is_empty()
+    }
+
+    fn expect(&mut self, actual: Exchange) -> Result<(), TransportError> {
+        match self.script.pop_front() {
+            Some(ref expected) if *expected == actual => Ok(()),
+            Some(Exchange::Error(e)) => Err(e),
+            expected => Err(unexpected(expected, format!("{:?}", actual))),
+        }
+    }
+}
+
+/// Describes a transfer which does not match the script.
+fn unexpected(expected: Option<Exchange>, actual: String) -> TransportError {
+    match expected {
+        Some(expected) => {
+            TransportError::Usb(format!("Expected {:?} but got {}", expected, actual))
+        }
+        None => TransportError::Usb(format!("Unexpected {} after the end of the script", actual)),
+    }
+}
+
+/// Copies the scripted `data` into `buffer`, failing like libusb if it does not fit.
+fn fill(buffer: &mut [u8], data: &[u8]) -> Result<usize, TransportError> {
+    if data.len() > buffer.len() {
+        return Err(TransportError::Overflow);
+    }
+    buffer[..data.len()].copy_from_slice(data);
+    Ok(data.len())
+}
+
+impl ProbeTransport for FakeTransport {
+    fn write_command(&mut self, command: &[u8]) -> Result<usize, TransportError> {
+        self.expect(Exchange::Command(command.to_vec()))?;
+        Ok(command.len())
+    }
+
+    fn bulk_out(&mut self, data: &[u8]) -> Result<usize, TransportError> {
+        self.expect(Exchange::BulkOut(data.to_vec()))?;
+        Ok(data.len())
+    }
+
+    fn bulk_in(&mut self, buffer: &mut [u8]) -> Result<usize, TransportError> {
+        match self.script.pop_front() {
+            Some(Exchange::BulkIn(data)) => fill(buffer, &data),
+            Some(Exchange::Error(e)) => Err(e),
+            expected => Err(unexpected(expected, "BulkIn".to_string())),
+        }
+    }
+
+    fn control_out(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> Result<usize, TransportError> {
+        self.expect(Exchange::ControlOut {
+            request_type,
+            request,
+            value,
+            index,
+            data: data.to_vec(),
+        })?;
+        Ok(data.len())
+    }
+
+    fn control_in(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buffer: &mut [u8],
+    ) -> Result<usize, TransportError> {
+        match self.script.pop_front() {
+            Some(Exchange::ControlIn {
+                request_type: t,
+                request: r,
+                value: v,
+                index: i,
+                ref data,
+            }) if (t, r, v, i) == (request_type, request, value, index) => fill(buffer, data),
+            Some(Exchange::Error(e)) => Err(e),
+            expected => Err(unexpected(
+                expected,
+                format!(
+                    "ControlIn {{ request_type: {}, request: {}, value: {}, index: {} }}",
+                    request_type, request, value, index
+                ),
+            )),
+        }
+    }
+
+    fn policy(&self) -> TransportPolicy {
+        self.policy
+    }
+
+    fn set_policy(&mut self, policy: TransportPolicy) {
+        self.policy = policy;
+    }
+}
+
+/// Wraps another `ProbeTransport` and records every exchange, including failed ones,
+/// so a session with a real probe can later be replayed with a `FakeTransport`.
+pub struct RecordingTransport<T: ProbeTransport> {
+    inner: T,
+    log: Vec<Exchange>,
+}
+
+impl<T: ProbeTransport> RecordingTransport<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            log: Vec::new(),
+        }
+    }
+
+    pub fn log(&self) -> &[Exchange] {
+        &self.log
+    }
+
+    /// Returns the wrapped transport and everything recorded so far.
+    pub fn into_parts(self) -> (T, Vec<Exchange>) {
+        (self.inner, self.log)
+    }
+
+    /// Logs the outcome of a transfer, building the exchange from the transferred length on success.
+    fn record<F>(
+        &mut self,
+        result: Result<usize, TransportError>,
+        exchange: F,
+    ) -> Result<usize, TransportError>
+    where
+        F: FnOnce(usize) -> Exchange,
+    {
+        self.log.push(match result {
+            Ok(len) => exchange(len),
+            Err(ref e) => Exchange::Error(e.clone()),
+        });
+        result
+    }
+}
+
+impl<T: ProbeTransport> ProbeTransport for RecordingTransport<T> {
+    fn write_command(&mut self, command: &[u8]) -> Result<usize, TransportError> {
+        let result = self.inner.write_command(command);
+        self.record(result, |written| {
+            Exchange::Command(command[..written].to_vec())
+        })
+    }
+
+    fn bulk_out(&mut self, data: &[u8]) -> Result<usize, TransportError> {
+        let result = self.inner.bulk_out(data);
+        self.record(result, |written| {
+            Exchange::BulkOut(data[..written].to_vec())
+        })
+    }
+
+    fn bulk_in(&mut self, buffer: &mut [u8]) -> Result<usize, TransportError> {
+        let result = self.inner.bulk_in(buffer);
+        self.record(result, |read| Exchange::BulkIn(buffer[..read].to_vec()))
+    }
+
+    fn control_out(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> Result<usize, TransportError> {
+        let result = self
+            .inner
+            .control_out(request_type, request, value, index, data);
+        self.record(result, |written| Exchange::ControlOut {
+            request_type,
+            request,
+            value,
+            index,
+            data: data[..written].to_vec(),
+        })
+    }
+
+    fn control_in(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buffer: &mut [u8],
+    ) -> Result<usize, TransportError> {
+        let result = self
+            .inner
+            .control_in(request_type, request, value, index, buffer);
+        self.record(result, |read| Exchange::ControlIn {
+            request_type,
+            request,
+            value,
+            index,
+            data: buffer[..read].to_vec(),
+        })
+    }
+
+    fn policy(&self) -> TransportPolicy {
+        self.inner.policy()
+    }
+
+    fn set_policy(&mut self, policy: TransportPolicy) {
+        self.inner.set_policy(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_script() {
+        let mut transport = FakeTransport::new(vec![
+            Exchange::Command(vec![0xF1]),
+            Exchange::BulkIn(vec![0x26, 0x00]),
+        ]);
+        assert_eq!(transport.write_command(&[0xF1]), Ok(1));
+        let mut buffer = [0u8; 6];
+        assert_eq!(transport.bulk_in(&mut buffer), Ok(2));
+        assert_eq!(buffer[..2], [0x26, 0x00]);
+        assert!(transport.is_done());
+    }
+
+    #[test]
+    fn rejects_unexpected_command() {
+        let mut transport = FakeTransport::new(vec![Exchange::Command(vec![0xF1])]);
+        assert!(transport.write_command(&[0xF2]).is_err());
+    }
+
+    #[test]
+    fn reports_transfers_after_end_of_script() {
+        let mut transport = FakeTransport::new(vec![]);
+        assert_eq!(
+            transport.write_command(&[0xF1]),
+            Err(TransportError::Usb(
+                "Unexpected Command([241]) after the end of the script".to_string()
+            ))
+        );
+        assert_eq!(
+            transport.bulk_in(&mut [0u8; 2]),
+            Err(TransportError::Usb(
+                "Unexpected BulkIn after the end of the script".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn reports_control_in_setup_mismatch() {
+        let mut transport = FakeTransport::new(vec![Exchange::ControlIn {
+            request_type: 0xC0,
+            request: 1,
+            value: 0,
+            index: 0,
+            data: vec![0x42],
+        }]);
+        match transport.control_in(0xC0, 2, 0, 0, &mut [0u8; 1]) {
+            Err(TransportError::Usb(message)) => assert!(message.ends_with(
+                "but got ControlIn { request_type: 192, request: 2, value: 0, index: 0 }"
+            )),
+            result => panic!("Unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn with_policy_restores_previous_policy() {
+        let mut transport = FakeTransport::new(vec![]);
+        let slow = TransportPolicy {
+            data_timeout: std::time::Duration::from_secs(30),
+            ..TransportPolicy::default()
+        };
+        let inside = transport.with_policy(slow, |t| t.policy());
+        assert_eq!(inside, slow);
+        assert_eq!(transport.policy(), TransportPolicy::default());
+    }
+
+    #[test]
+    fn recording_can_be_replayed() {
+        let script = vec![
+            Exchange::Command(vec![0xF2, 0x22]),
+            Exchange::BulkIn(vec![0x80]),
+        ];
+        let mut recorder = RecordingTransport::new(FakeTransport::new(script.clone()));
+        recorder.write_command(&[0xF2, 0x22]).unwrap();
+        recorder.bulk_in(&mut [0u8; 2]).unwrap();
+        let (inner, log) = recorder.into_parts();
+        assert!(inner.is_done());
+        assert_eq!(log, script);
+    }
+
+    #[test]
+    fn records_and_replays_failures() {
+        let script = vec![Exchange::Error(TransportError::Timeout)];
+        let mut recorder = RecordingTransport::new(FakeTransport::new(script.clone()));
+        assert_eq!(
+            recorder.bulk_in(&mut [0u8; 2]),
+            Err(TransportError::Timeout)
+        );
+        let (_, log) = recorder.into_parts();
+        assert_eq!(log, script);
+
+        let mut replay = FakeTransport::new(log);
+        assert_eq!(replay.write_command(&[0xF1]), Err(TransportError::Timeout));
+        assert!(replay.is_done());
+    }
+
+    #[test]
+    fn reports_overflow_instead_of_truncating() {
+        let mut transport = FakeTransport::new(vec![Exchange::BulkIn(vec![1, 2, 3])]);
+        assert_eq!(
+            transport.bulk_in(&mut [0u8; 2]),
+            Err(TransportError::Overflow)
+        );
+    }
+}
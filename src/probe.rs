@@ -1,45 +1,533 @@
+use crate::protocol::WireProtocol;
+use crate::transport::TransportError;
+
 pub struct ConnectedProbe<P: DebugProbe + Sized> {
     debug_probe: P,
 }
 
-impl ConnectedProbe {
+impl<P: DebugProbe> ConnectedProbe<P> {
+    pub fn new(debug_probe: P) -> Self {
+        Self { debug_probe }
+    }
 
+    pub fn probe(&self) -> &P {
+        &self.debug_probe
+    }
 }
 
+#[derive(Debug)]
 pub enum ProbeError {
     NotConnected,
-    ConnectionFailed(String)
+    ConnectionFailed(String),
+    /// The probe or its firmware does not support the requested operation.
+    NotSupported,
+    /// A `SessionConfig` asks for something the probe cannot do.
+    InvalidConfig(String),
+    /// The operating system refused access to the probe.
+    /// On Linux a udev rule granting access is suggested.
+    PermissionDenied {
+        suggested_udev_rule: Option<String>,
+    },
+    /// Communicating with the probe failed.
+    Transport(TransportError),
+}
+
+impl From<TransportError> for ProbeError {
+    fn from(error: TransportError) -> Self {
+        match error {
+            TransportError::PermissionDenied {
+                suggested_udev_rule,
+            } => ProbeError::PermissionDenied {
+                suggested_udev_rule,
+            },
+            e => ProbeError::Transport(e),
+        }
+    }
+}
+
+/// The way a target is reset when a probe connects to it or is asked to reset it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResetStrategy {
+    /// Pulse the nRST line of the target.
+    Hardware,
+    /// Request a system reset via AIRCR.SYSRESETREQ.
+    SysResetReq,
+    /// Reset only the core via AIRCR.VECTRESET.
+    VectReset,
+    /// Hold nRST asserted while the debug port is enabled and release it afterwards.
+    /// Required for chips which disable debug access from firmware.
+    ConnectUnderReset,
+}
+
+impl ResetStrategy {
+    /// Returns `true` if the strategy drives the nRST line and thus needs `ProbeCapabilities::NRST_CONTROL`.
+    pub fn uses_nrst(self) -> bool {
+        matches!(
+            self,
+            ResetStrategy::Hardware | ResetStrategy::ConnectUnderReset
+        )
+    }
+}
+
+/// Firmware versions reported by a probe.
+/// Components a probe does not have are `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProbeFirmware {
+    pub hw: u8,
+    pub jtag: u8,
+    pub swim: Option<u8>,
+    pub msc: Option<u8>,
+    pub bridge: Option<u8>,
+}
+
+bitflags::bitflags! {
+    /// Optional features a probe driver supports, so higher layers can pick a strategy up front.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ProbeCapabilities: u32 {
+        const SWO_CAPTURE = 1 << 0;
+        const MULTIDROP = 1 << 1;
+        const RAW_SWJ_SEQUENCES = 1 << 2;
+        const NRST_CONTROL = 1 << 3;
+        const TARGET_POWER_CONTROL = 1 << 4;
+        const RAW_JTAG_SEQUENCES = 1 << 5;
+        /// Target memory can be accessed while the core is running.
+        /// This is what allows RTT and live variable watches without halting the firmware.
+        const READ_WHILE_RUNNING = 1 << 6;
+    }
 }
 
 pub trait DebugProbe {
+    fn get_all_connected_probes() -> Vec<Self>
+    where
+        Self: Sized;
 
-    pub fn get_all_connected_probes();
-    
-    pub fn get_probe_with_id(unique_id: usize) -> DebugProbe;
-    
-    pub fn description(&self) -> String {
-        self.vendor_name() + " " + self.product_name()
+    fn get_probe_with_id(unique_id: usize) -> Result<Self, ProbeError>
+    where
+        Self: Sized;
+
+    fn description(&self) -> String {
+        self.vendor_name() + " " + &self.product_name()
     }
-    
-    pub fn vendor_name(&self) -> String;
-    
-    pub fn product_name(&self) -> String;
-    
-    pub fn get_supported_wire_protocols(self) -> Vec<WireProtocol>;
+
+    fn vendor_name(&self) -> String;
+
+    fn product_name(&self) -> String;
+
+    fn get_supported_wire_protocols(&self) -> Vec<WireProtocol>;
 
     /// Gets the unique id of a probe.
-    pub fn unique_id(&self) -> usize;
+    fn unique_id(&self) -> usize;
 
     /// Returns the currently selected `WireProtocol` if the probe is connected.
-    /// Returns `None` otherwise.
-    pub fn wire_protocol(&self) ->  -> Result<WireProtocol, ProbeError>;
-    
-    pub fn is_connected(&self) -> bool;
-
-    pub fn connect(&self) -> Result<(), ProbeError>;
-    
-    pub fn close(&self);
-
-    /// Sets the frequency for JTAG and SWD in Hz.
-    pub fn set_clock(self, frequency: usize);
-}
\ No newline at end of file
+    /// Returns `ProbeError::NotConnected` otherwise.
+    fn wire_protocol(&self) -> Result<WireProtocol, ProbeError>;
+
+    fn is_connected(&self) -> bool;
+
+    /// Validates `config` against this probe and connects to the target with it.
+    fn connect(&self, config: &SessionConfig) -> Result<(), ProbeError> {
+        config.validate(self)?;
+        self.connect_validated(config)
+    }
+
+    /// Performs the connect sequence for a configuration `connect` already validated:
+    /// selects protocol and clock, powers the target if requested and resets it if a reset strategy is set.
+    /// Without a reset strategy the target is left running as it is.
+    fn connect_validated(&self, config: &SessionConfig) -> Result<(), ProbeError>;
+
+    /// Resets the target using the given strategy.
+    /// Returns `ProbeError::InvalidConfig` for `ResetStrategy::ConnectUnderReset`,
+    /// which only has a meaning during `connect`, and `ProbeError::NotSupported` for
+    /// `ResetStrategy::Hardware` on probes without `ProbeCapabilities::NRST_CONTROL`.
+    fn target_reset(&self, strategy: ResetStrategy) -> Result<(), ProbeError> {
+        if strategy == ResetStrategy::ConnectUnderReset {
+            return Err(ProbeError::InvalidConfig(
+                "ConnectUnderReset can only be used when connecting".to_string(),
+            ));
+        }
+        if strategy.uses_nrst()
+            && !self
+                .capabilities()
+                .contains(ProbeCapabilities::NRST_CONTROL)
+        {
+            return Err(ProbeError::NotSupported);
+        }
+        self.perform_reset(strategy)
+    }
+
+    /// Performs the reset requested through `target_reset`.
+    /// Never called with `ResetStrategy::ConnectUnderReset`.
+    fn perform_reset(&self, strategy: ResetStrategy) -> Result<(), ProbeError>;
+
+    fn close(&self);
+
+    /// Returns the firmware versions of the probe.
+    fn firmware(&self) -> Result<ProbeFirmware, ProbeError>;
+
+    /// Returns a message advising a firmware upgrade if the current firmware lacks features this crate uses.
+    fn firmware_advisory(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns `true` if the firmware supports 16 bit memory transfers.
+    fn supports_16bit_transfers(&self) -> bool;
+
+    /// Returns `true` if the firmware can access other APs than AP 0.
+    fn supports_multiple_ap(&self) -> bool;
+
+    /// Returns the optional features this probe supports.
+    fn capabilities(&self) -> ProbeCapabilities;
+
+    /// Returns the largest number of bytes a single memory transfer can move.
+    fn max_memory_block_size(&self) -> usize;
+
+    /// Switches the power supply of the target on or off.
+    /// Only available on probes reporting `ProbeCapabilities::TARGET_POWER_CONTROL`.
+    fn set_target_power(&self, _enabled: bool) -> Result<(), ProbeError> {
+        Err(ProbeError::NotSupported)
+    }
+
+    /// Measures the target voltage in V.
+    fn target_voltage(&self) -> Result<f32, ProbeError> {
+        Err(ProbeError::NotSupported)
+    }
+
+    /// Measures the current drawn by the target in A.
+    fn target_current(&self) -> Result<f32, ProbeError> {
+        Err(ProbeError::NotSupported)
+    }
+
+    /// Reads back the current level of the nRST pin, `true` meaning the target is held in reset.
+    /// Polling this allows detecting resets triggered externally while connected.
+    fn nrst_asserted(&self) -> Result<bool, ProbeError> {
+        Err(ProbeError::NotSupported)
+    }
+
+    /// Moves the TAP through Test-Logic-Reset to Run-Test/Idle.
+    /// Only available on probes reporting `ProbeCapabilities::RAW_JTAG_SEQUENCES`.
+    fn jtag_tap_reset(&self) -> Result<(), ProbeError> {
+        Err(ProbeError::NotSupported)
+    }
+
+    /// Shifts the lowest `len` bits of `data` into the JTAG instruction register, LSB first,
+    /// and returns the bits shifted out in the same layout. The TAP ends in Run-Test/Idle.
+    /// Only available on probes reporting `ProbeCapabilities::RAW_JTAG_SEQUENCES`.
+    fn jtag_ir(&self, _data: &[u8], _len: usize) -> Result<Vec<u8>, ProbeError> {
+        Err(ProbeError::NotSupported)
+    }
+
+    /// Shifts the lowest `len` bits of `data` into the currently selected JTAG data register, LSB first,
+    /// and returns the bits shifted out in the same layout. The TAP ends in Run-Test/Idle.
+    /// Only available on probes reporting `ProbeCapabilities::RAW_JTAG_SEQUENCES`.
+    fn jtag_dr(&self, _data: &[u8], _len: usize) -> Result<Vec<u8>, ProbeError> {
+        Err(ProbeError::NotSupported)
+    }
+}
+
+/// Everything needed to connect to a target, checked against the probe before connecting.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionConfig {
+    pub protocol: WireProtocol,
+    /// Frequency for JTAG and SWD in Hz.
+    pub clock: usize,
+    /// How the target is reset while connecting.
+    /// `None`, the default, attaches without disturbing the running target.
+    pub reset_strategy: Option<ResetStrategy>,
+    /// Whether the probe should power the target before connecting.
+    pub power_target: bool,
+}
+
+impl SessionConfig {
+    pub fn new(protocol: WireProtocol) -> Self {
+        Self {
+            protocol,
+            clock: 1_000_000,
+            reset_strategy: None,
+            power_target: false,
+        }
+    }
+
+    pub fn clock(mut self, frequency: usize) -> Self {
+        self.clock = frequency;
+        self
+    }
+
+    pub fn reset_strategy(mut self, strategy: ResetStrategy) -> Self {
+        self.reset_strategy = Some(strategy);
+        self
+    }
+
+    pub fn power_target(mut self, enabled: bool) -> Self {
+        self.power_target = enabled;
+        self
+    }
+
+    /// Checks that `probe` supports everything this configuration asks for.
+    pub fn validate<P: DebugProbe + ?Sized>(&self, probe: &P) -> Result<(), ProbeError> {
+        if !probe
+            .get_supported_wire_protocols()
+            .contains(&self.protocol)
+        {
+            return Err(ProbeError::InvalidConfig(format!(
+                "{} does not support {:?}",
+                probe.description(),
+                self.protocol
+            )));
+        }
+        if self.clock == 0 {
+            return Err(ProbeError::InvalidConfig(
+                "The clock frequency must not be 0".to_string(),
+            ));
+        }
+        let capabilities = probe.capabilities();
+        if let Some(strategy) = self.reset_strategy {
+            if strategy.uses_nrst() && !capabilities.contains(ProbeCapabilities::NRST_CONTROL) {
+                return Err(ProbeError::InvalidConfig(format!(
+                    "{} cannot perform a {:?} reset without nRST control",
+                    probe.description(),
+                    strategy
+                )));
+            }
+        }
+        if self.power_target && !capabilities.contains(ProbeCapabilities::TARGET_POWER_CONTROL) {
+            return Err(ProbeError::InvalidConfig(format!(
+                "{} cannot power the target",
+                probe.description()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    struct StubProbe {
+        protocols: Vec<WireProtocol>,
+        capabilities: ProbeCapabilities,
+        connected: Cell<bool>,
+    }
+
+    impl StubProbe {
+        fn new(capabilities: ProbeCapabilities) -> Self {
+            Self {
+                protocols: vec![WireProtocol::Swd],
+                capabilities,
+                connected: Cell::new(false),
+            }
+        }
+    }
+
+    impl DebugProbe for StubProbe {
+        fn get_all_connected_probes() -> Vec<Self> {
+            vec![]
+        }
+
+        fn get_probe_with_id(_unique_id: usize) -> Result<Self, ProbeError> {
+            Err(ProbeError::NotConnected)
+        }
+
+        fn vendor_name(&self) -> String {
+            "Stub".to_string()
+        }
+
+        fn product_name(&self) -> String {
+            "Probe".to_string()
+        }
+
+        fn get_supported_wire_protocols(&self) -> Vec<WireProtocol> {
+            self.protocols.clone()
+        }
+
+        fn unique_id(&self) -> usize {
+            0
+        }
+
+        fn wire_protocol(&self) -> Result<WireProtocol, ProbeError> {
+            Err(ProbeError::NotConnected)
+        }
+
+        fn is_connected(&self) -> bool {
+            self.connected.get()
+        }
+
+        fn connect_validated(&self, _config: &SessionConfig) -> Result<(), ProbeError> {
+            self.connected.set(true);
+            Ok(())
+        }
+
+        fn perform_reset(&self, _strategy: ResetStrategy) -> Result<(), ProbeError> {
+            Ok(())
+        }
+
+        fn close(&self) {}
+
+        fn firmware(&self) -> Result<ProbeFirmware, ProbeError> {
+            Err(ProbeError::NotSupported)
+        }
+
+        fn supports_16bit_transfers(&self) -> bool {
+            false
+        }
+
+        fn supports_multiple_ap(&self) -> bool {
+            false
+        }
+
+        fn capabilities(&self) -> ProbeCapabilities {
+            self.capabilities
+        }
+
+        fn max_memory_block_size(&self) -> usize {
+            64
+        }
+    }
+
+    fn is_invalid_config(result: Result<(), ProbeError>) -> bool {
+        matches!(result, Err(ProbeError::InvalidConfig(_)))
+    }
+
+    #[test]
+    fn accepts_supported_config() {
+        let probe = StubProbe::new(ProbeCapabilities::NRST_CONTROL);
+        let config = SessionConfig::new(WireProtocol::Swd).reset_strategy(ResetStrategy::Hardware);
+        assert!(config.validate(&probe).is_ok());
+    }
+
+    #[test]
+    fn does_not_reset_by_default() {
+        assert_eq!(SessionConfig::new(WireProtocol::Swd).reset_strategy, None);
+    }
+
+    #[test]
+    fn rejects_unsupported_protocol() {
+        let probe = StubProbe::new(ProbeCapabilities::all());
+        assert!(is_invalid_config(
+            SessionConfig::new(WireProtocol::Jtag).validate(&probe)
+        ));
+    }
+
+    #[test]
+    fn rejects_zero_clock() {
+        let probe = StubProbe::new(ProbeCapabilities::all());
+        assert!(is_invalid_config(
+            SessionConfig::new(WireProtocol::Swd)
+                .clock(0)
+                .validate(&probe)
+        ));
+    }
+
+    #[test]
+    fn rejects_nrst_resets_without_nrst_control() {
+        for &strategy in &[ResetStrategy::Hardware, ResetStrategy::ConnectUnderReset] {
+            let config = SessionConfig::new(WireProtocol::Swd).reset_strategy(strategy);
+            let probe = StubProbe::new(ProbeCapabilities::empty());
+            assert!(is_invalid_config(config.validate(&probe)));
+            let probe = StubProbe::new(ProbeCapabilities::NRST_CONTROL);
+            assert!(config.validate(&probe).is_ok());
+        }
+        let config =
+            SessionConfig::new(WireProtocol::Swd).reset_strategy(ResetStrategy::SysResetReq);
+        assert!(config
+            .validate(&StubProbe::new(ProbeCapabilities::empty()))
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_target_power_without_power_control() {
+        let config = SessionConfig::new(WireProtocol::Swd).power_target(true);
+        let probe = StubProbe::new(ProbeCapabilities::empty());
+        assert!(is_invalid_config(config.validate(&probe)));
+        let probe = StubProbe::new(ProbeCapabilities::TARGET_POWER_CONTROL);
+        assert!(config.validate(&probe).is_ok());
+    }
+
+    #[test]
+    fn connect_validates_config_first() {
+        let probe = StubProbe::new(ProbeCapabilities::empty());
+        assert!(is_invalid_config(
+            probe.connect(&SessionConfig::new(WireProtocol::Jtag))
+        ));
+        assert!(!probe.is_connected());
+        assert!(probe
+            .connect(&SessionConfig::new(WireProtocol::Swd))
+            .is_ok());
+        assert!(probe.is_connected());
+    }
+
+    #[test]
+    fn optional_operations_default_to_not_supported() {
+        let probe = StubProbe::new(ProbeCapabilities::empty());
+        assert!(!probe
+            .capabilities()
+            .contains(ProbeCapabilities::TARGET_POWER_CONTROL));
+        assert!(matches!(
+            probe.set_target_power(true),
+            Err(ProbeError::NotSupported)
+        ));
+        assert!(matches!(
+            probe.target_voltage(),
+            Err(ProbeError::NotSupported)
+        ));
+        assert!(matches!(
+            probe.nrst_asserted(),
+            Err(ProbeError::NotSupported)
+        ));
+        assert!(matches!(
+            probe.jtag_ir(&[0x0E], 4),
+            Err(ProbeError::NotSupported)
+        ));
+        assert!(matches!(
+            probe.jtag_dr(&[0; 4], 32),
+            Err(ProbeError::NotSupported)
+        ));
+        assert!(matches!(
+            probe.jtag_tap_reset(),
+            Err(ProbeError::NotSupported)
+        ));
+        assert_eq!(probe.firmware_advisory(), None);
+    }
+
+    #[test]
+    fn forwards_permission_denied() {
+        let rule = Some("rule".to_string());
+        match ProbeError::from(TransportError::PermissionDenied {
+            suggested_udev_rule: rule.clone(),
+        }) {
+            ProbeError::PermissionDenied {
+                suggested_udev_rule,
+            } => assert_eq!(suggested_udev_rule, rule),
+            e => panic!("Unexpected {:?}", e),
+        }
+        assert!(matches!(
+            ProbeError::from(TransportError::Timeout),
+            ProbeError::Transport(TransportError::Timeout)
+        ));
+    }
+
+    #[test]
+    fn target_reset_rejects_connect_under_reset() {
+        let probe = StubProbe::new(ProbeCapabilities::all());
+        assert!(is_invalid_config(
+            probe.target_reset(ResetStrategy::ConnectUnderReset)
+        ));
+        assert!(probe.target_reset(ResetStrategy::SysResetReq).is_ok());
+    }
+
+    #[test]
+    fn target_reset_needs_nrst_control_for_hardware_reset() {
+        let probe = StubProbe::new(ProbeCapabilities::empty());
+        assert!(matches!(
+            probe.target_reset(ResetStrategy::Hardware),
+            Err(ProbeError::NotSupported)
+        ));
+        assert!(probe.target_reset(ResetStrategy::SysResetReq).is_ok());
+        let probe = StubProbe::new(ProbeCapabilities::NRST_CONTROL);
+        assert!(probe.target_reset(ResetStrategy::Hardware).is_ok());
+    }
+}
@@ -1,3 +1,5 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum WireProtocol {
     Swd,
     Jtag
@@ -0,0 +1,548 @@
+use std::thread;
+use std::time::Duration;
+
+use crate::probe::{DebugProbe, ProbeCapabilities, ProbeError};
+
+/// TCK frequency used to turn `RUNTEST` clock counts into a time if the file sets no `FREQUENCY`.
+/// This is the default clock of a `SessionConfig`.
+const DEFAULT_FREQUENCY: f64 = 1_000_000.0;
+
+#[derive(Debug)]
+pub enum SvfError {
+    /// A statement is malformed.
+    Syntax {
+        line: usize,
+        message: String,
+    },
+    /// A valid statement which cannot be executed with the JTAG operations of `DebugProbe`.
+    Unsupported {
+        line: usize,
+        statement: String,
+    },
+    /// The bits shifted out of the chain differ from the expected `TDO` in a bit selected by `MASK`.
+    TdoMismatch {
+        line: usize,
+    },
+    Probe(ProbeError),
+}
+
+impl From<ProbeError> for SvfError {
+    fn from(error: ProbeError) -> Self {
+        SvfError::Probe(error)
+    }
+}
+
+/// Plays an SVF file through the raw JTAG operations of `probe`,
+/// so e.g. a CPLD in the same chain as the MCU can be programmed.
+///
+/// Every scan passes Run-Test/Idle, so `IDLE` is the only supported end state and `STATE` can only
+/// reset the TAP or move it to Run-Test/Idle. `RUNTEST` waits for the requested time instead of
+/// clocking TCK, which suffices for programming delays but not for devices counting the clocks.
+/// Binary XSVF files are not supported.
+pub fn play<P: DebugProbe + ?Sized>(probe: &P, svf: &str) -> Result<(), SvfError> {
+    if !probe
+        .capabilities()
+        .contains(ProbeCapabilities::RAW_JTAG_SEQUENCES)
+    {
+        return Err(SvfError::Probe(ProbeError::NotSupported));
+    }
+    let mut player = Player::new(probe);
+    for statement in statements(svf)? {
+        player.execute(&statement)?;
+    }
+    Ok(())
+}
+
+struct Statement {
+    line: usize,
+    tokens: Vec<String>,
+}
+
+impl Statement {
+    fn syntax(&self, message: &str) -> SvfError {
+        SvfError::Syntax {
+            line: self.line,
+            message: message.to_string(),
+        }
+    }
+
+    fn unsupported(&self) -> SvfError {
+        SvfError::Unsupported {
+            line: self.line,
+            statement: self.tokens.join(" "),
+        }
+    }
+
+    fn number(&self, token: &str) -> Result<f64, SvfError> {
+        token
+            .parse()
+            .map_err(|_| self.syntax(&format!("Invalid number {}", token)))
+    }
+}
+
+/// Splits `svf` into statements, dropping comments.
+fn statements(svf: &str) -> Result<Vec<Statement>, SvfError> {
+    let mut statements = Vec::new();
+    let mut text = String::new();
+    let mut start = None;
+    for (index, line) in svf.lines().enumerate() {
+        let end = [line.find('!'), line.find("//")]
+            .iter()
+            .flatten()
+            .min()
+            .copied()
+            .unwrap_or(line.len());
+        for c in line[..end].chars() {
+            if c == ';' {
+                if let Some(line) = start.take() {
+                    statements.push(Statement {
+                        line,
+                        tokens: tokenize(line, &text)?,
+                    });
+                }
+                text.clear();
+            } else {
+                if start.is_none() && !c.is_whitespace() {
+                    start = Some(index + 1);
+                }
+                text.push(c);
+            }
+        }
+        text.push(' ');
+    }
+    match start {
+        Some(line) => Err(SvfError::Syntax {
+            line,
+            message: "Statement is not terminated by ;".to_string(),
+        }),
+        None => Ok(statements),
+    }
+}
+
+/// Splits a statement into upper case keywords and the hex strings in parentheses.
+fn tokenize(line: usize, text: &str) -> Result<Vec<String>, SvfError> {
+    let spaced = text.replace('(', " ( ").replace(')', " ) ");
+    let mut words = spaced.split_whitespace();
+    let mut tokens = Vec::new();
+    while let Some(word) = words.next() {
+        if word == "(" {
+            let mut value = String::new();
+            loop {
+                match words.next() {
+                    Some(")") => break,
+                    Some(part) => value.push_str(part),
+                    None => {
+                        return Err(SvfError::Syntax {
+                            line,
+                            message: "Missing )".to_string(),
+                        })
+                    }
+                }
+            }
+            tokens.push(value);
+        } else {
+            tokens.push(word.to_uppercase());
+        }
+    }
+    Ok(tokens)
+}
+
+/// Returns bit `index` of an LSB first bit string.
+fn bit(bits: &[u8], index: usize) -> bool {
+    bits.get(index / 8)
+        .is_some_and(|byte| byte >> (index % 8) & 1 == 1)
+}
+
+/// Concatenates bit strings, the first one ending up in the lowest bits.
+fn concat(parts: &[(&[u8], usize)]) -> (Vec<u8>, usize) {
+    let total: usize = parts.iter().map(|&(_, len)| len).sum();
+    let mut bits = vec![0; total.div_ceil(8)];
+    let mut offset = 0;
+    for &(part, len) in parts {
+        for index in 0..len {
+            if bit(part, index) {
+                bits[(offset + index) / 8] |= 1 << ((offset + index) % 8);
+            }
+        }
+        offset += len;
+    }
+    (bits, total)
+}
+
+/// Converts an SVF hex string, most significant digit first, into the lowest `len` bits of an LSB first bit string.
+fn parse_hex(statement: &Statement, hex: &str, len: usize) -> Result<Vec<u8>, SvfError> {
+    let mut bits = vec![0; len.div_ceil(8)];
+    for (digit_index, digit) in hex.chars().rev().enumerate() {
+        let nibble = digit
+            .to_digit(16)
+            .ok_or_else(|| statement.syntax(&format!("Invalid hex string {}", hex)))?;
+        for nibble_bit in 0..4 {
+            let index = digit_index * 4 + nibble_bit;
+            if index < len && nibble >> nibble_bit & 1 == 1 {
+                bits[index / 8] |= 1 << (index % 8);
+            }
+        }
+    }
+    Ok(bits)
+}
+
+/// The parameters of a `SIR`, `SDR` or header/trailer statement.
+struct ScanFields {
+    len: usize,
+    tdi: Option<Vec<u8>>,
+    tdo: Option<Vec<u8>>,
+    mask: Option<Vec<u8>>,
+}
+
+impl ScanFields {
+    fn parse(statement: &Statement) -> Result<Self, SvfError> {
+        let len = match statement.tokens.get(1) {
+            Some(token) => statement.number(token)?,
+            None => return Err(statement.syntax("Missing length")),
+        };
+        if len < 0.0 || len.fract() != 0.0 {
+            return Err(statement.syntax("Invalid length"));
+        }
+        let mut fields = ScanFields {
+            len: len as usize,
+            tdi: None,
+            tdo: None,
+            mask: None,
+        };
+        for pair in statement.tokens[2..].chunks(2) {
+            let value = match pair.get(1) {
+                Some(hex) => parse_hex(statement, hex, fields.len)?,
+                None => return Err(statement.syntax(&format!("Missing value for {}", pair[0]))),
+            };
+            match pair[0].as_str() {
+                "TDI" => fields.tdi = Some(value),
+                "TDO" => fields.tdo = Some(value),
+                "MASK" => fields.mask = Some(value),
+                "SMASK" => {}
+                key => return Err(statement.syntax(&format!("Unknown parameter {}", key))),
+            }
+        }
+        Ok(fields)
+    }
+}
+
+/// Bits shifted before or after every scan of a register, to address one device in a chain.
+#[derive(Default)]
+struct Pattern {
+    len: usize,
+    tdi: Vec<u8>,
+}
+
+/// The last scan of a register. `TDI` and `MASK` carry over to the next scan of the same length.
+#[derive(Default)]
+struct Scan {
+    len: usize,
+    tdi: Vec<u8>,
+    mask: Vec<u8>,
+}
+
+struct Player<'a, P: DebugProbe + ?Sized> {
+    probe: &'a P,
+    frequency: Option<f64>,
+    header_ir: Pattern,
+    trailer_ir: Pattern,
+    header_dr: Pattern,
+    trailer_dr: Pattern,
+    ir: Scan,
+    dr: Scan,
+}
+
+impl<'a, P: DebugProbe + ?Sized> Player<'a, P> {
+    fn new(probe: &'a P) -> Self {
+        Self {
+            probe,
+            frequency: None,
+            header_ir: Pattern::default(),
+            trailer_ir: Pattern::default(),
+            header_dr: Pattern::default(),
+            trailer_dr: Pattern::default(),
+            ir: Scan::default(),
+            dr: Scan::default(),
+        }
+    }
+
+    fn execute(&mut self, statement: &Statement) -> Result<(), SvfError> {
+        let argument = statement.tokens.get(1).map(String::as_str);
+        match statement.tokens[0].as_str() {
+            "SIR" => self.scan(statement, true)?,
+            "SDR" => self.scan(statement, false)?,
+            "HIR" => self.header_ir = pattern(statement)?,
+            "TIR" => self.trailer_ir = pattern(statement)?,
+            "HDR" => self.header_dr = pattern(statement)?,
+            "TDR" => self.trailer_dr = pattern(statement)?,
+            "ENDIR" | "ENDDR" if argument == Some("IDLE") => {}
+            "TRST" if argument != Some("ON") => {}
+            "STATE" => {
+                for state in &statement.tokens[1..] {
+                    match state.as_str() {
+                        "RESET" => self.probe.jtag_tap_reset()?,
+                        "IDLE" => {}
+                        _ => return Err(statement.unsupported()),
+                    }
+                }
+            }
+            "FREQUENCY" => {
+                self.frequency = match argument {
+                    Some(frequency) => Some(statement.number(frequency)?),
+                    None => None,
+                }
+            }
+            "RUNTEST" => self.run_test(statement)?,
+            _ => return Err(statement.unsupported()),
+        }
+        Ok(())
+    }
+
+    fn scan(&mut self, statement: &Statement, ir: bool) -> Result<(), SvfError> {
+        let fields = ScanFields::parse(statement)?;
+        let (header, trailer, last) = if ir {
+            (&self.header_ir, &self.trailer_ir, &mut self.ir)
+        } else {
+            (&self.header_dr, &self.trailer_dr, &mut self.dr)
+        };
+        let same_len = fields.len == last.len;
+        let tdi = match fields.tdi {
+            Some(tdi) => tdi,
+            None if same_len => last.tdi.clone(),
+            None => return Err(statement.syntax("Missing TDI")),
+        };
+        let mask = match fields.mask {
+            Some(mask) => mask,
+            None if same_len => last.mask.clone(),
+            None => vec![0xFF; fields.len.div_ceil(8)],
+        };
+
+        let (bits, len) = concat(&[
+            (&header.tdi, header.len),
+            (&tdi, fields.len),
+            (&trailer.tdi, trailer.len),
+        ]);
+        let tdo = if ir {
+            self.probe.jtag_ir(&bits, len)?
+        } else {
+            self.probe.jtag_dr(&bits, len)?
+        };
+        if let Some(expected) = fields.tdo {
+            let mismatch = (0..fields.len)
+                .any(|i| bit(&mask, i) && bit(&tdo, header.len + i) != bit(&expected, i));
+            if mismatch {
+                return Err(SvfError::TdoMismatch {
+                    line: statement.line,
+                });
+            }
+        }
+
+        *last = Scan {
+            len: fields.len,
+            tdi,
+            mask,
+        };
+        Ok(())
+    }
+
+    fn run_test(&self, statement: &Statement) -> Result<(), SvfError> {
+        let mut seconds: f64 = 0.0;
+        let mut tokens = statement.tokens[1..].iter().map(String::as_str);
+        while let Some(token) = tokens.next() {
+            match token {
+                "IDLE" => {}
+                "ENDSTATE" if tokens.next() == Some("IDLE") => {}
+                "MAXIMUM" => {
+                    tokens.next();
+                    tokens.next();
+                }
+                "ENDSTATE" | "RESET" | "IRPAUSE" | "DRPAUSE" => return Err(statement.unsupported()),
+                value => {
+                    let value = statement.number(value)?;
+                    match tokens.next() {
+                        Some("SEC") => seconds = seconds.max(value),
+                        Some("TCK") => {
+                            let frequency = self.frequency.unwrap_or(DEFAULT_FREQUENCY);
+                            seconds = seconds.max(value / frequency);
+                        }
+                        _ => return Err(statement.unsupported()),
+                    }
+                }
+            }
+        }
+        thread::sleep(Duration::from_secs_f64(seconds));
+        Ok(())
+    }
+}
+
+fn pattern(statement: &Statement) -> Result<Pattern, SvfError> {
+    let fields = ScanFields::parse(statement)?;
+    let tdi = match fields.tdi {
+        Some(tdi) => tdi,
+        None => vec![0; fields.len.div_ceil(8)],
+    };
+    Ok(Pattern {
+        len: fields.len,
+        tdi,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::{ProbeFirmware, ResetStrategy, SessionConfig};
+    use crate::protocol::WireProtocol;
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+
+    /// Records all JTAG operations and answers data register scans with scripted TDO bits.
+    #[derive(Default)]
+    struct JtagProbe {
+        shifts: RefCell<Vec<(&'static str, Vec<u8>, usize)>>,
+        tdo: RefCell<VecDeque<Vec<u8>>>,
+        resets: Cell<usize>,
+    }
+
+    impl DebugProbe for JtagProbe {
+        fn get_all_connected_probes() -> Vec<Self> {
+            vec![]
+        }
+
+        fn get_probe_with_id(_unique_id: usize) -> Result<Self, ProbeError> {
+            Err(ProbeError::NotConnected)
+        }
+
+        fn vendor_name(&self) -> String {
+            "Stub".to_string()
+        }
+
+        fn product_name(&self) -> String {
+            "JTAG".to_string()
+        }
+
+        fn get_supported_wire_protocols(&self) -> Vec<WireProtocol> {
+            vec![WireProtocol::Jtag]
+        }
+
+        fn unique_id(&self) -> usize {
+            0
+        }
+
+        fn wire_protocol(&self) -> Result<WireProtocol, ProbeError> {
+            Ok(WireProtocol::Jtag)
+        }
+
+        fn is_connected(&self) -> bool {
+            true
+        }
+
+        fn connect_validated(&self, _config: &SessionConfig) -> Result<(), ProbeError> {
+            Ok(())
+        }
+
+        fn perform_reset(&self, _strategy: ResetStrategy) -> Result<(), ProbeError> {
+            Ok(())
+        }
+
+        fn close(&self) {}
+
+        fn firmware(&self) -> Result<ProbeFirmware, ProbeError> {
+            Err(ProbeError::NotSupported)
+        }
+
+        fn supports_16bit_transfers(&self) -> bool {
+            false
+        }
+
+        fn supports_multiple_ap(&self) -> bool {
+            false
+        }
+
+        fn capabilities(&self) -> ProbeCapabilities {
+            ProbeCapabilities::RAW_JTAG_SEQUENCES
+        }
+
+        fn max_memory_block_size(&self) -> usize {
+            64
+        }
+
+        fn jtag_tap_reset(&self) -> Result<(), ProbeError> {
+            self.resets.set(self.resets.get() + 1);
+            Ok(())
+        }
+
+        fn jtag_ir(&self, data: &[u8], len: usize) -> Result<Vec<u8>, ProbeError> {
+            self.shifts.borrow_mut().push(("ir", data.to_vec(), len));
+            Ok(vec![0; data.len()])
+        }
+
+        fn jtag_dr(&self, data: &[u8], len: usize) -> Result<Vec<u8>, ProbeError> {
+            self.shifts.borrow_mut().push(("dr", data.to_vec(), len));
+            Ok(self
+                .tdo
+                .borrow_mut()
+                .pop_front()
+                .unwrap_or_else(|| vec![0; data.len()]))
+        }
+    }
+
+    #[test]
+    fn shifts_scans_with_header_and_trailer() {
+        let probe = JtagProbe::default();
+        let svf = "! select the CPLD behind a 2 bit IR\n\
+                   STATE RESET IDLE;\n\
+                   HIR 2 TDI (3);\n\
+                   SIR 4 TDI (e);\n\
+                   TDR 1 TDI (0);\n\
+                   SDR 16 TDI (12\n34); // split over two lines\n\
+                   SDR 16;\n";
+        play(&probe, svf).unwrap();
+        assert_eq!(probe.resets.get(), 1);
+        assert_eq!(
+            *probe.shifts.borrow(),
+            vec![
+                ("ir", vec![0x3B], 6),
+                ("dr", vec![0x34, 0x12, 0x00], 17),
+                ("dr", vec![0x34, 0x12, 0x00], 17),
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_masked_tdo() {
+        let probe = JtagProbe::default();
+        probe.tdo.borrow_mut().extend(vec![vec![0x35], vec![0x34]]);
+        let svf = "SDR 8 TDI (00) TDO (A5) MASK (0F);\nSDR 8 TDI (00) TDO (A5);\n";
+        assert!(matches!(
+            play(&probe, svf),
+            Err(SvfError::TdoMismatch { line: 2 })
+        ));
+    }
+
+    #[test]
+    fn waits_for_runtest() {
+        let probe = JtagProbe::default();
+        play(
+            &probe,
+            "FREQUENCY 1E6 HZ;\nRUNTEST IDLE 1000 TCK 1.0E-03 SEC ENDSTATE IDLE;\n",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_unsupported_statements() {
+        let probe = JtagProbe::default();
+        assert!(matches!(
+            play(&probe, "ENDDR DRPAUSE;"),
+            Err(SvfError::Unsupported { line: 1, .. })
+        ));
+        assert!(matches!(
+            play(&probe, "SDR 8 TDI (00)"),
+            Err(SvfError::Syntax { line: 1, .. })
+        ));
+        assert!(matches!(
+            play(&probe, "SIR 8;"),
+            Err(SvfError::Syntax { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+/// A bounded in-memory buffer for SWO/RTT data sitting between a producer and a possibly slow consumer.
+///
+/// When the buffer is full the oldest bytes are evicted. They are written to the spill writer
+/// if one is configured and counted as dropped otherwise, so data is never lost silently.
+/// A high-water callback lets the producer react before that happens.
+pub struct TraceBuffer {
+    data: VecDeque<u8>,
+    capacity: usize,
+    spill: Option<Box<dyn Write + Send>>,
+    high_water: Option<HighWater>,
+    dropped: usize,
+}
+
+struct HighWater {
+    mark: usize,
+    callback: Box<dyn FnMut(usize) + Send>,
+    triggered: bool,
+}
+
+impl TraceBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: VecDeque::with_capacity(capacity),
+            capacity,
+            spill: None,
+            high_water: None,
+            dropped: 0,
+        }
+    }
+
+    /// Writes evicted bytes to `writer`, e.g. a file, instead of dropping them.
+    /// The spilled data precedes everything still held in the buffer.
+    pub fn spill_to<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.spill = Some(Box::new(writer));
+        self
+    }
+
+    /// Calls `callback` with the current fill level once the buffer holds `mark` bytes or more.
+    /// The callback fires again only after the fill level dropped below `mark` in between.
+    pub fn on_high_water<F: FnMut(usize) + Send + 'static>(
+        mut self,
+        mark: usize,
+        callback: F,
+    ) -> Self {
+        self.high_water = Some(HighWater {
+            mark,
+            callback: Box::new(callback),
+            triggered: false,
+        });
+        self
+    }
+
+    /// Appends `bytes`, evicting the oldest data if the buffer is full.
+    /// Fails only if writing evicted data to the spill writer fails. The new data is appended
+    /// anyway and the evicted bytes are counted as dropped, as they may not have reached the writer.
+    pub fn push(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut result = Ok(());
+        let overflow = (self.data.len() + bytes.len()).saturating_sub(self.capacity);
+        if overflow > 0 {
+            let evicted_from_buffer = overflow.min(self.data.len());
+            let evicted_from_input = overflow - evicted_from_buffer;
+            let mut evicted: Vec<u8> = self.data.drain(..evicted_from_buffer).collect();
+            evicted.extend_from_slice(&bytes[..evicted_from_input]);
+            match self.spill.as_mut().map(|spill| spill.write_all(&evicted)) {
+                Some(Ok(())) => {}
+                Some(Err(e)) => {
+                    self.dropped += overflow;
+                    result = Err(e);
+                }
+                None => self.dropped += overflow,
+            }
+            self.data.extend(&bytes[evicted_from_input..]);
+        } else {
+            self.data.extend(bytes);
+        }
+
+        let len = self.data.len();
+        if let Some(ref mut high_water) = self.high_water {
+            if len >= high_water.mark && !high_water.triggered {
+                high_water.triggered = true;
+                (high_water.callback)(len);
+            }
+        }
+        result
+    }
+
+    /// Moves the oldest buffered bytes into `buffer` and returns how many were moved.
+    pub fn pop(&mut self, buffer: &mut [u8]) -> usize {
+        let len = buffer.len().min(self.data.len());
+        for (target, byte) in buffer.iter_mut().zip(self.data.drain(..len)) {
+            *target = byte;
+        }
+        if let Some(ref mut high_water) = self.high_water {
+            if self.data.len() < high_water.mark {
+                high_water.triggered = false;
+            }
+        }
+        len
+    }
+
+    /// Iterates over the buffered bytes, oldest first, without consuming them.
+    pub fn replay(&self) -> impl Iterator<Item = u8> + '_ {
+        self.data.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of evicted bytes which were not written to a spill writer.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A writer whose contents can still be inspected after it was moved into the buffer.
+    #[derive(Clone, Default)]
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn evicts_oldest_and_counts_drops() {
+        let mut buffer = TraceBuffer::new(4);
+        buffer.push(&[1, 2, 3]).unwrap();
+        buffer.push(&[4, 5, 6]).unwrap();
+        assert_eq!(buffer.replay().collect::<Vec<_>>(), vec![3, 4, 5, 6]);
+        assert_eq!(buffer.dropped(), 2);
+
+        buffer.push(&[7, 8, 9, 10, 11]).unwrap();
+        assert_eq!(buffer.replay().collect::<Vec<_>>(), vec![8, 9, 10, 11]);
+        assert_eq!(buffer.dropped(), 7);
+    }
+
+    #[test]
+    fn spills_evicted_bytes_in_order() {
+        let spill = SharedWriter::default();
+        let mut buffer = TraceBuffer::new(3).spill_to(spill.clone());
+        buffer.push(&[1, 2]).unwrap();
+        buffer.push(&[3, 4, 5, 6, 7]).unwrap();
+        assert_eq!(*spill.0.lock().unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(buffer.replay().collect::<Vec<_>>(), vec![5, 6, 7]);
+        assert_eq!(buffer.dropped(), 0);
+    }
+
+    #[test]
+    fn failed_spill_counts_drops_and_keeps_new_data() {
+        let mut buffer = TraceBuffer::new(4).spill_to(FailingWriter);
+        buffer.push(&[1, 2, 3]).unwrap();
+        assert!(buffer.push(&[4, 5, 6]).is_err());
+        assert_eq!(buffer.replay().collect::<Vec<_>>(), vec![3, 4, 5, 6]);
+        assert_eq!(buffer.dropped(), 2);
+    }
+
+    #[test]
+    fn pop_consumes_but_replay_does_not() {
+        let mut buffer = TraceBuffer::new(8);
+        buffer.push(b"hello").unwrap();
+        assert_eq!(buffer.replay().count(), 5);
+        let mut out = [0u8; 3];
+        assert_eq!(buffer.pop(&mut out), 3);
+        assert_eq!(&out, b"hel");
+        assert_eq!(buffer.replay().collect::<Vec<_>>(), b"lo".to_vec());
+    }
+
+    #[test]
+    fn high_water_fires_once_until_drained() {
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let recorded = levels.clone();
+        let mut buffer =
+            TraceBuffer::new(8).on_high_water(4, move |len| recorded.lock().unwrap().push(len));
+        buffer.push(&[0; 3]).unwrap();
+        buffer.push(&[0; 2]).unwrap();
+        buffer.push(&[0; 1]).unwrap();
+        assert_eq!(*levels.lock().unwrap(), vec![5]);
+
+        buffer.pop(&mut [0; 4]);
+        buffer.push(&[0; 2]).unwrap();
+        assert_eq!(*levels.lock().unwrap(), vec![5, 4]);
+    }
+}
@@ -0,0 +1,335 @@
+use std::time::Duration;
+
+/// Timeouts and retry behaviour of a `ProbeTransport`.
+///
+/// A session sets a policy once; single operations such as flash erase status polls,
+/// which take far longer than register reads, can override it with `ProbeTransport::with_policy`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransportPolicy {
+    /// Timeout for sending a command packet.
+    pub command_timeout: Duration,
+    /// Timeout for data phases and responses.
+    pub data_timeout: Duration,
+    /// How often an IN transfer which timed out is retried.
+    /// OUT transfers are never retried, as a timed out write may already have partially
+    /// reached the probe and resending it would desync the probe protocol.
+    ///
+    /// Retrying reads is not free of that risk either: libusb discards the data of a read
+    /// which timed out after receiving part of a reply, and the retry then returns the rest
+    /// of it. Only raise this above the default of 0 for operations whose replies fit in a
+    /// single packet, such as status polls.
+    pub retries: usize,
+    /// Time to wait before each retry.
+    pub backoff: Duration,
+}
+
+impl Default for TransportPolicy {
+    fn default() -> Self {
+        Self {
+            command_timeout: Duration::from_millis(1000),
+            data_timeout: Duration::from_millis(1000),
+            retries: 0,
+            backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportError {
+    Timeout,
+    NoDevice,
+    /// The probe sent more data than the buffer could hold.
+    Overflow,
+    /// The operating system refused access to the device.
+    /// On Linux this usually means a udev rule granting access to the probe is missing.
+    /// A rule is only suggested when the error comes from `LibUsbTransport::open`,
+    /// as the device ids are not known for failing transfers.
+    PermissionDenied {
+        suggested_udev_rule: Option<String>,
+    },
+    Usb(String),
+}
+
+/// Returns a udev rule granting the logged in user access to the USB device with the given ids.
+/// It has to be installed as e.g. `/etc/udev/rules.d/69-dbg-probe.rules`; `uaccess` tags are only
+/// applied by rule files sorting before `73-seat-late.rules`.
+pub fn udev_rule(vendor_id: u16, product_id: u16) -> String {
+    format!(
+        r#"SUBSYSTEMS=="usb", ATTRS{{idVendor}}=="{:04x}", ATTRS{{idProduct}}=="{:04x}", TAG+="uaccess""#,
+        vendor_id, product_id
+    )
+}
+
+impl From<libusb::Error> for TransportError {
+    fn from(error: libusb::Error) -> Self {
+        match error {
+            libusb::Error::Timeout => TransportError::Timeout,
+            libusb::Error::NoDevice => TransportError::NoDevice,
+            libusb::Error::Overflow => TransportError::Overflow,
+            libusb::Error::Access => TransportError::PermissionDenied {
+                suggested_udev_rule: None,
+            },
+            e => TransportError::Usb(e.to_string()),
+        }
+    }
+}
+
+/// The raw channel a probe driver talks to its probe through.
+///
+/// Drivers only ever use this trait, so they can be run against a mock transport
+/// and other USB backends can be added without touching driver logic.
+pub trait ProbeTransport {
+    /// Sends a command packet to the probe.
+    fn write_command(&mut self, command: &[u8]) -> Result<usize, TransportError>;
+
+    /// Sends the data phase following a command.
+    fn bulk_out(&mut self, data: &[u8]) -> Result<usize, TransportError>;
+
+    /// Reads a response or data phase into `buffer`.
+    /// Returns the number of bytes actually read.
+    fn bulk_in(&mut self, buffer: &mut [u8]) -> Result<usize, TransportError>;
+
+    /// Performs a control transfer from the host to the probe.
+    fn control_out(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> Result<usize, TransportError>;
+
+    /// Performs a control transfer from the probe to the host.
+    /// Returns the number of bytes actually read.
+    fn control_in(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buffer: &mut [u8],
+    ) -> Result<usize, TransportError>;
+
+    /// Returns the policy currently in effect.
+    fn policy(&self) -> TransportPolicy {
+        TransportPolicy::default()
+    }
+
+    /// Replaces the policy used for all following transfers.
+    fn set_policy(&mut self, _policy: TransportPolicy) {}
+
+    /// Runs `operation` with `policy` in effect and restores the previous policy afterwards.
+    fn with_policy<R, F>(&mut self, policy: TransportPolicy, operation: F) -> R
+    where
+        Self: Sized,
+        F: FnOnce(&mut Self) -> R,
+    {
+        let previous = self.policy();
+        self.set_policy(policy);
+        let result = operation(self);
+        self.set_policy(previous);
+        result
+    }
+}
+
+/// A `ProbeTransport` on top of an opened libusb device.
+pub struct LibUsbTransport<'a> {
+    handle: libusb::DeviceHandle<'a>,
+    command_endpoint: u8,
+    out_endpoint: u8,
+    in_endpoint: u8,
+    policy: TransportPolicy,
+}
+
+impl<'a> LibUsbTransport<'a> {
+    pub fn new(
+        handle: libusb::DeviceHandle<'a>,
+        command_endpoint: u8,
+        out_endpoint: u8,
+        in_endpoint: u8,
+    ) -> Self {
+        Self {
+            handle,
+            command_endpoint,
+            out_endpoint,
+            in_endpoint,
+            policy: TransportPolicy::default(),
+        }
+    }
+
+    /// Opens `device` and wraps it, reporting missing permissions with an actionable error.
+    /// Wrap the result with `LoggingTransport::from_env` to honour `DBG_PROBE_USB_LOG`.
+    pub fn open(
+        device: &libusb::Device<'a>,
+        command_endpoint: u8,
+        out_endpoint: u8,
+        in_endpoint: u8,
+    ) -> Result<Self, TransportError> {
+        match device.open() {
+            Ok(handle) => Ok(Self::new(
+                handle,
+                command_endpoint,
+                out_endpoint,
+                in_endpoint,
+            )),
+            Err(libusb::Error::Access) => {
+                let suggested_udev_rule = if cfg!(target_os = "linux") {
+                    device
+                        .device_descriptor()
+                        .ok()
+                        .map(|d| udev_rule(d.vendor_id(), d.product_id()))
+                } else {
+                    None
+                };
+                Err(TransportError::PermissionDenied {
+                    suggested_udev_rule,
+                })
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Runs the IN transfer `transfer` with the given timeout, retrying it on timeouts as `policy` allows.
+/// See `TransportPolicy::retries` for when this is safe.
+fn retry_in<F>(
+    policy: &TransportPolicy,
+    timeout: Duration,
+    mut transfer: F,
+) -> Result<usize, TransportError>
+where
+    F: FnMut(Duration) -> libusb::Result<usize>,
+{
+    let mut retries = policy.retries;
+    loop {
+        match transfer(timeout) {
+            Err(libusb::Error::Timeout) if retries > 0 => {
+                retries -= 1;
+                std::thread::sleep(policy.backoff);
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+impl<'a> ProbeTransport for LibUsbTransport<'a> {
+    fn write_command(&mut self, command: &[u8]) -> Result<usize, TransportError> {
+        Ok(self
+            .handle
+            .write_bulk(self.command_endpoint, command, self.policy.command_timeout)?)
+    }
+
+    fn bulk_out(&mut self, data: &[u8]) -> Result<usize, TransportError> {
+        Ok(self
+            .handle
+            .write_bulk(self.out_endpoint, data, self.policy.data_timeout)?)
+    }
+
+    fn bulk_in(&mut self, buffer: &mut [u8]) -> Result<usize, TransportError> {
+        let endpoint = self.in_endpoint;
+        retry_in(&self.policy, self.policy.data_timeout, |timeout| {
+            self.handle.read_bulk(endpoint, buffer, timeout)
+        })
+    }
+
+    fn control_out(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> Result<usize, TransportError> {
+        Ok(self.handle.write_control(
+            request_type,
+            request,
+            value,
+            index,
+            data,
+            self.policy.command_timeout,
+        )?)
+    }
+
+    fn control_in(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buffer: &mut [u8],
+    ) -> Result<usize, TransportError> {
+        retry_in(&self.policy, self.policy.data_timeout, |timeout| {
+            self.handle
+                .read_control(request_type, request, value, index, buffer, timeout)
+        })
+    }
+
+    fn policy(&self) -> TransportPolicy {
+        self.policy
+    }
+
+    fn set_policy(&mut self, policy: TransportPolicy) {
+        self.policy = policy;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(retries: usize) -> TransportPolicy {
+        TransportPolicy {
+            retries,
+            backoff: Duration::from_millis(0),
+            ..TransportPolicy::default()
+        }
+    }
+
+    #[test]
+    fn retries_timed_out_in_transfers() {
+        let mut timeouts = 2;
+        let mut transfer = |_| {
+            if timeouts > 0 {
+                timeouts -= 1;
+                Err(libusb::Error::Timeout)
+            } else {
+                Ok(4)
+            }
+        };
+        assert_eq!(
+            retry_in(&policy(2), Duration::from_millis(1), &mut transfer),
+            Ok(4)
+        );
+    }
+
+    #[test]
+    fn gives_up_after_configured_retries() {
+        let mut attempts = 0;
+        let result = retry_in(&policy(1), Duration::from_millis(1), |_| {
+            attempts += 1;
+            Err(libusb::Error::Timeout)
+        });
+        assert_eq!(result, Err(TransportError::Timeout));
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn does_not_retry_other_errors() {
+        let mut attempts = 0;
+        let result = retry_in(&policy(3), Duration::from_millis(1), |_| {
+            attempts += 1;
+            Err(libusb::Error::Pipe)
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn formats_udev_rule() {
+        assert_eq!(
+            udev_rule(0x0483, 0x374b),
+            r#"SUBSYSTEMS=="usb", ATTRS{idVendor}=="0483", ATTRS{idProduct}=="374b", TAG+="uaccess""#
+        );
+    }
+}
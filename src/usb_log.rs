@@ -0,0 +1,184 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::transport::{ProbeTransport, TransportError, TransportPolicy};
+
+/// Name of the environment variable holding the path of the USB traffic log.
+pub const USB_LOG_ENV: &str = "DBG_PROBE_USB_LOG";
+
+/// Wraps another `ProbeTransport` and writes every transfer as one JSON object per line.
+///
+/// Each line holds a timestamp in microseconds since the UNIX epoch, the kind of transfer,
+/// the transferred bytes as a hex string and either `"ok"` or the error that occurred.
+///
+/// Transports are not wrapped automatically: whoever opens one, e.g. with `LibUsbTransport::open`,
+/// has to pass it through `LoggingTransport::from_env` for `DBG_PROBE_USB_LOG` to take effect.
+pub struct LoggingTransport<T: ProbeTransport, W: Write> {
+    inner: T,
+    writer: Option<W>,
+}
+
+impl<T: ProbeTransport> LoggingTransport<T, BufWriter<File>> {
+    /// Logs to the file named by `DBG_PROBE_USB_LOG` if it is set, otherwise passes all transfers through.
+    /// The file is buffered and flushed when the transport is dropped.
+    pub fn from_env(inner: T) -> Self {
+        let writer = std::env::var_os(USB_LOG_ENV).and_then(|path| match File::create(&path) {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(e) => {
+                log::warn!("Could not create USB log {:?}: {}", path, e);
+                None
+            }
+        });
+        Self { inner, writer }
+    }
+}
+
+impl<T: ProbeTransport, W: Write> LoggingTransport<T, W> {
+    pub fn new(inner: T, writer: W) -> Self {
+        Self {
+            inner,
+            writer: Some(writer),
+        }
+    }
+
+    pub fn into_parts(self) -> (T, Option<W>) {
+        (self.inner, self.writer)
+    }
+
+    fn log(
+        &mut self,
+        kind: &str,
+        setup: Option<(u8, u8, u16, u16)>,
+        data: &[u8],
+        result: &Result<usize, TransportError>,
+    ) {
+        let writer = match self.writer {
+            Some(ref mut writer) => writer,
+            None => return,
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros())
+            .unwrap_or(0);
+        let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+        let setup = match setup {
+            Some((request_type, request, value, index)) => format!(
+                r#","request_type":{},"request":{},"value":{},"index":{}"#,
+                request_type, request, value, index
+            ),
+            None => String::new(),
+        };
+        let result = match result {
+            Ok(_) => "ok".to_string(),
+            Err(e) => format!("{:?}", e)
+                .replace('\\', "\\\\")
+                .replace('"', "\\\""),
+        };
+        if let Err(e) = writeln!(
+            writer,
+            r#"{{"timestamp_us":{},"kind":"{}"{},"data":"{}","result":"{}"}}"#,
+            timestamp, kind, setup, hex, result
+        ) {
+            log::warn!("Could not write USB log: {}", e);
+        }
+    }
+}
+
+impl<T: ProbeTransport, W: Write> ProbeTransport for LoggingTransport<T, W> {
+    fn write_command(&mut self, command: &[u8]) -> Result<usize, TransportError> {
+        let result = self.inner.write_command(command);
+        self.log("command", None, command, &result);
+        result
+    }
+
+    fn bulk_out(&mut self, data: &[u8]) -> Result<usize, TransportError> {
+        let result = self.inner.bulk_out(data);
+        self.log("bulk_out", None, data, &result);
+        result
+    }
+
+    fn bulk_in(&mut self, buffer: &mut [u8]) -> Result<usize, TransportError> {
+        let result = self.inner.bulk_in(buffer);
+        let read = *result.as_ref().unwrap_or(&0);
+        self.log("bulk_in", None, &buffer[..read], &result);
+        result
+    }
+
+    fn control_out(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> Result<usize, TransportError> {
+        let result = self
+            .inner
+            .control_out(request_type, request, value, index, data);
+        self.log(
+            "control_out",
+            Some((request_type, request, value, index)),
+            data,
+            &result,
+        );
+        result
+    }
+
+    fn control_in(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buffer: &mut [u8],
+    ) -> Result<usize, TransportError> {
+        let result = self
+            .inner
+            .control_in(request_type, request, value, index, buffer);
+        let read = *result.as_ref().unwrap_or(&0);
+        self.log(
+            "control_in",
+            Some((request_type, request, value, index)),
+            &buffer[..read],
+            &result,
+        );
+        result
+    }
+
+    fn policy(&self) -> TransportPolicy {
+        self.inner.policy()
+    }
+
+    fn set_policy(&mut self, policy: TransportPolicy) {
+        self.inner.set_policy(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Exchange, FakeTransport};
+
+    #[test]
+    fn logs_one_line_per_transfer() {
+        let fake = FakeTransport::new(vec![
+            Exchange::Command(vec![0xF1, 0x80]),
+            Exchange::BulkIn(vec![0x26]),
+        ]);
+        let mut transport = LoggingTransport::new(fake, Vec::new());
+        transport.write_command(&[0xF1, 0x80]).unwrap();
+        transport.bulk_in(&mut [0u8; 4]).unwrap();
+        assert!(transport.bulk_in(&mut [0u8; 4]).is_err());
+
+        let (_, log) = transport.into_parts();
+        let log = String::from_utf8(log.unwrap()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains(r#""kind":"command","data":"f180","result":"ok""#));
+        assert!(lines[1].contains(r#""kind":"bulk_in","data":"26","result":"ok""#));
+        assert!(
+            lines[2].contains(r#""kind":"bulk_in","data":"","result":"Usb(\"Unexpected BulkIn"#)
+        );
+    }
+}